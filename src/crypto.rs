@@ -0,0 +1,139 @@
+/// Minimal SHA-1 / HMAC-SHA1 / PBKDF2 implementation used to authenticate
+/// against password-protected OATH applets (see RFC 3174, RFC 2104 and
+/// RFC 2898). Hand-rolled rather than pulled in as a dependency, matching
+/// the rest of this crate's preference for doing its own byte plumbing.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+
+const BLOCK_SIZE: usize = 64;
+const OUTPUT_SIZE: usize = 20;
+
+pub fn sha1(data: &[u8]) -> [u8; OUTPUT_SIZE] {
+    let mut h0: u32 = 0x67452301;
+    let mut h1: u32 = 0xEFCDAB89;
+    let mut h2: u32 = 0x98BADCFE;
+    let mut h3: u32 = 0x10325476;
+    let mut h4: u32 = 0xC3D2E1F0;
+
+    let bit_len = (data.len() as u64) * 8;
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+
+    while msg.len() % BLOCK_SIZE != 56 {
+        msg.push(0);
+    }
+
+    msg.write_u64::<BigEndian>(bit_len).unwrap();
+
+    for chunk in msg.chunks(BLOCK_SIZE) {
+        let mut w = [0u32; 80];
+        let mut rdr = Cursor::new(chunk);
+
+        for word in w.iter_mut().take(16) {
+            *word = rdr.read_u32::<BigEndian>().unwrap();
+        }
+
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h0, h1, h2, h3, h4);
+
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = if i < 20 {
+                ((b & c) | ((!b) & d), 0x5A827999u32)
+            } else if i < 40 {
+                (b ^ c ^ d, 0x6ED9EBA1u32)
+            } else if i < 60 {
+                ((b & c) | (b & d) | (c & d), 0x8F1BBCDCu32)
+            } else {
+                (b ^ c ^ d, 0xCA62C1D6u32)
+            };
+
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        h0 = h0.wrapping_add(a);
+        h1 = h1.wrapping_add(b);
+        h2 = h2.wrapping_add(c);
+        h3 = h3.wrapping_add(d);
+        h4 = h4.wrapping_add(e);
+    }
+
+    let mut digest = Vec::with_capacity(OUTPUT_SIZE);
+    digest.write_u32::<BigEndian>(h0).unwrap();
+    digest.write_u32::<BigEndian>(h1).unwrap();
+    digest.write_u32::<BigEndian>(h2).unwrap();
+    digest.write_u32::<BigEndian>(h3).unwrap();
+    digest.write_u32::<BigEndian>(h4).unwrap();
+
+    let mut out = [0u8; OUTPUT_SIZE];
+    out.copy_from_slice(&digest);
+    out
+}
+
+pub fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; OUTPUT_SIZE] {
+    let mut key_block = [0u8; BLOCK_SIZE];
+
+    if key.len() > BLOCK_SIZE {
+        key_block[..OUTPUT_SIZE].copy_from_slice(&sha1(key));
+    } else {
+        key_block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut ipad = [0x36u8; BLOCK_SIZE];
+    let mut opad = [0x5cu8; BLOCK_SIZE];
+
+    for i in 0..BLOCK_SIZE {
+        ipad[i] ^= key_block[i];
+        opad[i] ^= key_block[i];
+    }
+
+    let mut inner = ipad.to_vec();
+    inner.extend_from_slice(message);
+
+    let mut outer = opad.to_vec();
+    outer.extend_from_slice(&sha1(&inner));
+
+    sha1(&outer)
+}
+
+pub fn pbkdf2_hmac_sha1(password: &[u8], salt: &[u8], iterations: u32, dk_len: usize) -> Vec<u8> {
+    let mut derived = Vec::with_capacity(dk_len);
+    let mut block_index: u32 = 1;
+
+    while derived.len() < dk_len {
+        let mut salt_block = salt.to_vec();
+        salt_block.write_u32::<BigEndian>(block_index).unwrap();
+
+        let mut block = hmac_sha1(password, &salt_block);
+        let mut u = block;
+
+        for _ in 1..iterations {
+            u = hmac_sha1(password, &u);
+
+            for i in 0..OUTPUT_SIZE {
+                block[i] ^= u[i];
+            }
+        }
+
+        derived.extend_from_slice(&block);
+        block_index += 1;
+    }
+
+    derived.truncate(dk_len);
+    derived
+}
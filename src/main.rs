@@ -13,6 +13,7 @@ use notify_rust::Notification;
 use gtk::prelude::*;
 use gtk::{WidgetExt, MenuShellExt, MenuItemExt};
 use libappindicator::{AppIndicator, AppIndicatorStatus};
+use std::time::Duration;
 use ykoath;
 
 fn notify_code_copied() {
@@ -62,7 +63,29 @@ fn update_menu(indicator: &mut AppIndicator) -> gtk::Continue {
         let device_entry = gtk::MenuItem::new_with_label(&device_label);
         let child_menu = gtk::Menu::new();
         let builder = gtk::Builder::new();
-        let codes = match yubikey.get_oath_codes() {
+        // Connect to the card and open a transaction to talk to the OATH applet
+        let card_ctx = pcsc::Context::establish(pcsc::Scope::User).unwrap();
+        let mut card = match card_ctx.connect(
+            &std::ffi::CString::new(yubikey.name).unwrap(),
+            pcsc::ShareMode::Shared,
+            pcsc::Protocols::ANY
+        ) {
+            Ok(card) => card,
+            Err(e) => {
+                println!("ERROR {}", e);
+                continue;
+            },
+        };
+        let tx = match card.transaction() {
+            Ok(tx) => tx,
+            Err(e) => {
+                println!("ERROR {}", e);
+                continue;
+            },
+        };
+        let transport = ykoath::PcscTransport::new(&tx);
+
+        let codes = match yubikey.get_oath_codes(&transport, None) {
             Ok(codes) => codes,
             Err(e) => {
                 println!("ERROR {}", e);
@@ -78,7 +101,35 @@ fn update_menu(indicator: &mut AppIndicator) -> gtk::Continue {
 
         // Enumerate the OATH codes and create a child menu for each device
         for oath in codes {
-            let code = ykoath::format_code(oath.code.value, oath.code.digits);
+            // HOTP and touch-required credentials aren't computed by
+            // CalculateAll, so fetch those individually
+            let code = match oath.code {
+                Some(code) if oath.steam => ykoath::format_steam_code(code.value),
+                Some(code) => ykoath::format_code(code.value, code.digits),
+                None => {
+                    // A single, non-blocking attempt: this runs on the GTK
+                    // main thread, so we can't afford calculate_code's full
+                    // touch-wait retry loop here. A credential that isn't
+                    // touched in time just waits for the next poll.
+                    let calculated = yubikey.calculate_code(
+                        &transport,
+                        &oath.name,
+                        oath.oath_type,
+                        None,
+                        1,
+                        Duration::from_millis(0),
+                    );
+
+                    match calculated {
+                        Ok(code) if oath.steam => ykoath::format_steam_code(code.value),
+                        Ok(code) => ykoath::format_code(code.value, code.digits),
+                        Err(e) => {
+                            println!("ERROR {}", e);
+                            continue;
+                        },
+                    }
+                },
+            };
             let name_clone = oath.name.clone();
             let mut label_vec: Vec<&str> = name_clone.split(":").collect();
             let mut code_entry_label: String = String::from(